@@ -0,0 +1,99 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Pixel;
+
+/// Size in bytes of one log record: `timestamp_ms:u64, x:u32, y:u32, r:u8, g:u8, b:u8`.
+const RECORD_LEN: usize = 19;
+
+pub struct LogRecord {
+    pub timestamp_ms: u64,
+    pub x: u32,
+    pub y: u32,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Append-only, fixed-width record of every pixel accepted since the last
+/// snapshot, so the canvas's history can be replayed for the timelapse
+/// endpoint. The log is truncated back to empty whenever a fresh snapshot is
+/// saved to disk, since the snapshot then covers everything it contained.
+pub struct PlacementLog {
+    path: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl PlacementLog {
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap();
+
+        Self {
+            path,
+            writer: Mutex::new(BufWriter::new(file)),
+        }
+    }
+
+    pub fn append(&self, pixel: &Pixel) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        let mut record = [0u8; RECORD_LEN];
+        record[0..8].copy_from_slice(&timestamp_ms.to_le_bytes());
+        record[8..12].copy_from_slice(&pixel.x.to_le_bytes());
+        record[12..16].copy_from_slice(&pixel.y.to_le_bytes());
+        record[16] = pixel.r;
+        record[17] = pixel.g;
+        record[18] = pixel.b;
+
+        self.writer.lock().unwrap().write_all(&record).unwrap();
+    }
+
+    pub fn flush(&self) {
+        self.writer.lock().unwrap().flush().unwrap();
+    }
+
+    /// Truncates the log in place. Call this right after the base snapshot
+    /// on disk has been rewritten.
+    pub fn rotate(&self) {
+        let mut writer = self.writer.lock().unwrap();
+        writer.flush().unwrap();
+        let file = writer.get_mut();
+        file.set_len(0).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+    }
+
+    /// Reads every record currently on disk, in the order they were
+    /// appended.
+    pub fn read_records(&self) -> Vec<LogRecord> {
+        self.flush();
+
+        let mut reader = BufReader::new(File::open(&self.path).unwrap());
+        let mut records = Vec::new();
+        let mut buf = [0u8; RECORD_LEN];
+
+        while reader.read_exact(&mut buf).is_ok() {
+            records.push(LogRecord {
+                timestamp_ms: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+                x: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+                y: u32::from_le_bytes(buf[12..16].try_into().unwrap()),
+                r: buf[16],
+                g: buf[17],
+                b: buf[18],
+            });
+        }
+
+        records
+    }
+}
+
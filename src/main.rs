@@ -1,51 +1,125 @@
+use std::collections::HashMap;
 use std::io::Cursor;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex, RwLock, mpsc};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use async_signal::{Signal, Signals};
-use futures_util::StreamExt;
-use image::{ImageFormat, ImageReader, Rgb, RgbImage};
+use futures_util::{SinkExt, StreamExt};
+use image::codecs::gif::GifEncoder;
+use image::{Frame, ImageFormat, ImageReader, Rgb, RgbImage};
 use poem::endpoint::StaticFileEndpoint;
 use poem::http::StatusCode;
 use poem::listener::TcpListener;
 use poem::middleware::Tracing;
-use poem::web::{Data, Json};
-use poem::{EndpointExt, IntoResponse, Response, Route, Server, handler};
+use poem::web::websocket::{Message, WebSocket};
+use poem::web::{Data, Json, Query};
+use poem::{Body, Endpoint, EndpointExt, IntoResponse, Request, Response, Route, Server, handler};
 use serde::Deserialize;
+use tokio::sync::{broadcast, watch};
+use tokio_stream::wrappers::WatchStream;
+
+use crate::history::PlacementLog;
+use crate::rate_limit::{RateLimiter, client_ip};
+use crate::tls::TlsPaths;
+use crate::ws::{PIXEL_RECORD_LEN, decode_pixel, encode_pixel};
+
+mod history;
+mod rate_limit;
+mod tls;
+mod ws;
+
+/// How often the background thread evicts stale rate-limit buckets.
+const RATE_LIMIT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of pending pixel batches a `/ws` subscriber may lag behind before
+/// it's dropped instead of blocking the apply thread.
+const PIXEL_BROADCAST_CAPACITY: usize = 1024;
+
+/// How often the background thread flushes the placement log to disk.
+const LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Upper bound on the number of frames a single `/timelapse` request may
+/// render, to keep one request from encoding an unbounded GIF.
+const MAX_TIMELAPSE_FRAMES: u32 = 240;
+
+const PLACEMENT_LOG_PATH: &str = "data/placements.log";
+
+/// Default cap on how many pixels a single `POST /pixels` batch may contain,
+/// overridable with `MAX_BATCH_SIZE`. It equals the rate limiter's default
+/// capacity, which a batch may not exceed.
+const DEFAULT_MAX_BATCH_SIZE: usize = 10;
 
 #[derive(Clone)]
 struct ServerState {
     canvas: Arc<RwLock<RgbImage>>,
     canvas_size: (u32, u32),
-    canvas_cache: Arc<Mutex<Option<CanvasCache<Vec<u8>>>>>,
+    canvas_cache: Arc<Mutex<HashMap<CacheKey, CanvasCache<Vec<u8>>>>>,
     queue: Arc<Sender<Pixel>>,
+    rate_limiter: Arc<RateLimiter>,
+    trusted_proxy: bool,
+    pixel_updates: broadcast::Sender<Arc<[u8]>>,
+    /// Bumped by the apply thread every time it writes a batch of pixels.
+    generation: Arc<AtomicU64>,
+    placement_log: Arc<PlacementLog>,
+    /// The canvas as it was when the process started, i.e. what the
+    /// placement log's records replay on top of for `/timelapse`.
+    base_image: Arc<RgbImage>,
+    max_batch_size: usize,
+}
+
+/// Identifies one rendered representation of the canvas: either the full
+/// image or a rectangular tile, encoded in a particular format.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    region: Option<(u32, u32, u32, u32)>,
+    format: ImageFormat,
 }
 
 struct CanvasCache<T> {
     data: T,
-    updated_at: Instant,
+    generation: u64,
+    rendered_at: Instant,
+    system_time: SystemTime,
 }
 
 impl<T> CanvasCache<T> {
-    fn new(data: T) -> Self {
+    fn new(data: T, generation: u64) -> Self {
         Self {
             data,
-            updated_at: Instant::now(),
+            generation,
+            rendered_at: Instant::now(),
+            system_time: SystemTime::now(),
         }
     }
 
-    fn get(&self) -> Option<&T> {
-        if self.updated_at.elapsed() >= Duration::from_millis(100) {
-            return None;
-        }
+    /// A cache entry genuinely reflects `generation`: it's safe to hand its
+    /// ETag/`Last-Modified` to a client and let them 304 against it.
+    fn is_fresh(&self, generation: u64) -> bool {
+        self.generation == generation
+    }
 
-        Some(&self.data)
+    /// A cache entry is worth reusing as a render shortcut if it's fresh, or
+    /// if it's recent enough that re-rendering would just be redundant work
+    /// during a burst of writes. A reused entry is served with its own
+    /// ETag/`Last-Modified`, which describe the generation its bytes were
+    /// rendered for.
+    fn is_reusable(&self, generation: u64) -> bool {
+        self.is_fresh(generation) || self.rendered_at.elapsed() < Duration::from_millis(100)
+    }
+
+    fn etag(&self, generation: u64) -> String
+    where
+        T: AsRef<[u8]>,
+    {
+        format!("W/\"{generation}-{}\"", self.data.as_ref().len())
     }
 }
 
 #[derive(Deserialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
 struct Pixel {
     x: u32,
     y: u32,
@@ -54,75 +128,522 @@ struct Pixel {
     b: u8,
 }
 
-#[handler]
-#[expect(clippy::needless_pass_by_value)]
-fn get_image(state: Data<&ServerState>) -> Response {
-    if let Some(data) = state
+/// Returns `true` if the request's validators show the client already has
+/// the current representation cached.
+fn is_cache_hit(req: &Request, etag: &str, last_modified: SystemTime) -> bool {
+    if let Some(if_none_match) = req.header("If-None-Match") {
+        return if_none_match.trim() == etag;
+    }
+
+    if let Some(if_modified_since) = req.header("If-Modified-Since") {
+        if let Ok(since) = httpdate::parse_http_date(if_modified_since) {
+            return last_modified <= since;
+        }
+    }
+
+    false
+}
+
+/// Parses a `?format=` value into the `ImageFormat` it names, or `None` if
+/// it isn't one of the formats this server knows how to encode.
+fn parse_format(format: &str) -> Option<ImageFormat> {
+    match format {
+        "png" => Some(ImageFormat::Png),
+        "webp" => Some(ImageFormat::WebP),
+        "bmp" => Some(ImageFormat::Bmp),
+        _ => None,
+    }
+}
+
+fn content_type_for(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::WebP => "image/webp",
+        ImageFormat::Bmp => "image/bmp",
+        _ => "image/png",
+    }
+}
+
+/// Picks the response format: an explicit `?format=` wins, otherwise `Accept`
+/// is consulted (preferring WebP when the client advertises it), falling
+/// back to PNG.
+fn negotiate_format(query_format: Option<&str>, accept: Option<&str>) -> Result<ImageFormat, Response> {
+    if let Some(format) = query_format {
+        return parse_format(format).ok_or_else(|| {
+            StatusCode::BAD_REQUEST
+                .with_body(format!("unsupported format `{format}`"))
+                .into_response()
+        });
+    }
+
+    if accept.is_some_and(|accept| accept.contains("image/webp")) {
+        return Ok(ImageFormat::WebP);
+    }
+
+    Ok(ImageFormat::Png)
+}
+
+fn encode_image(image: &RgbImage, format: ImageFormat) -> Result<Vec<u8>, image::ImageError> {
+    let mut buffer = Cursor::new(Vec::new());
+    image.write_to(&mut buffer, format)?;
+    Ok(buffer.into_inner())
+}
+
+/// Renders (or reuses a cached rendering of) the representation identified
+/// by `key`, calling `render` only on a cache miss. The returned ETag and
+/// `Last-Modified` always describe the returned bytes: when those are reused
+/// from a slightly older, still-`is_reusable` entry, they carry that entry's
+/// generation, so the client revalidates against the next fresh render
+/// instead of being told "not modified" for it. Within the 100 ms reuse
+/// window a conditional GET can still be answered "not modified" while a
+/// newer write has not been rendered yet.
+fn render_cached(
+    state: &ServerState,
+    generation: u64,
+    key: CacheKey,
+    render: impl FnOnce() -> Result<Vec<u8>, image::ImageError>,
+) -> Result<(Vec<u8>, String, SystemTime), image::ImageError> {
+    if let Some(entry) = state
         .canvas_cache
         .lock()
         .unwrap()
-        .as_ref()
-        .and_then(|cache| cache.get())
+        .get(&key)
+        .filter(|entry| entry.is_reusable(generation))
     {
-        let data = data.clone();
-        return Response::from(data);
+        // Possibly reused from an older generation to skip a redundant encode
+        // during a write burst: report the generation these bytes were
+        // actually rendered for.
+        return Ok((entry.data.clone(), entry.etag(entry.generation), entry.system_time));
     }
 
-    let mut buffer = Cursor::new(Vec::new());
+    // Render without holding the cache lock: a concurrent request for a
+    // different (or the same) key shouldn't have to wait on this encode.
+    let entry = CanvasCache::new(render()?, generation);
+    let result = (entry.data.clone(), entry.etag(generation), entry.system_time);
 
-    state
-        .canvas
-        .read()
-        .unwrap()
-        .write_to(&mut buffer, ImageFormat::Png)
-        .unwrap();
+    let mut cache = state.canvas_cache.lock().unwrap();
+    cache.insert(key, entry);
+    cache.retain(|_, entry| entry.is_reusable(generation));
+    Ok(result)
+}
 
-    let data = buffer.into_inner();
-    *state.canvas_cache.lock().unwrap() = Some(CanvasCache::new(data.clone()));
+fn conditional_image_response(
+    req: &Request,
+    data: Vec<u8>,
+    etag: String,
+    last_modified: SystemTime,
+    content_type: &'static str,
+) -> Response {
+    let last_modified_header = httpdate::fmt_http_date(last_modified);
+
+    if is_cache_hit(req, &etag, last_modified) {
+        return StatusCode::NOT_MODIFIED
+            .with_header("Vary", "Accept")
+            .with_header("ETag", etag)
+            .with_header("Last-Modified", last_modified_header)
+            .into_response();
+    }
 
     Response::from(data)
-        .set_content_type("image/png")
-        .with_header("Cache-Control", "no-store")
+        .set_content_type(content_type)
+        .with_header("Cache-Control", "no-cache")
+        .with_header("Vary", "Accept")
+        .with_header("ETag", etag)
+        .with_header("Last-Modified", last_modified_header)
         .into_response()
 }
 
+#[derive(Deserialize)]
+struct ImageQuery {
+    format: Option<String>,
+}
+
 #[handler]
 #[expect(clippy::needless_pass_by_value)]
-fn set_pixel(state: Data<&ServerState>, Json(json): Json<Pixel>) -> Response {
+fn get_image(state: Data<&ServerState>, req: &Request, Query(query): Query<ImageQuery>) -> Response {
+    let format = match negotiate_format(query.format.as_deref(), req.header("Accept")) {
+        Ok(format) => format,
+        Err(response) => return response,
+    };
+
+    let generation = state.generation.load(Ordering::Acquire);
+    let key = CacheKey { region: None, format };
+
+    let Ok((data, etag, last_modified)) = render_cached(state.0, generation, key, || {
+        encode_image(&state.canvas.read().unwrap(), format)
+    }) else {
+        return StatusCode::INTERNAL_SERVER_ERROR
+            .with_body("failed to encode image")
+            .into_response();
+    };
+
+    conditional_image_response(req, data, etag, last_modified, content_type_for(format))
+}
+
+#[derive(Deserialize)]
+struct TileQuery {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    format: Option<String>,
+}
+
+/// Returns `true` if the `(x, y, w, h)` tile region is non-empty and lies
+/// entirely within a canvas of `canvas_size`.
+fn tile_in_bounds(canvas_size: (u32, u32), region: (u32, u32, u32, u32)) -> bool {
+    let (x, y, w, h) = region;
+    w != 0 && h != 0 && x.saturating_add(w) <= canvas_size.0 && y.saturating_add(h) <= canvas_size.1
+}
+
+#[handler]
+#[expect(clippy::needless_pass_by_value)]
+fn get_image_tile(
+    state: Data<&ServerState>,
+    req: &Request,
+    Query(query): Query<TileQuery>,
+) -> Response {
+    let format = match negotiate_format(query.format.as_deref(), req.header("Accept")) {
+        Ok(format) => format,
+        Err(response) => return response,
+    };
+
+    let region = (query.x, query.y, query.w, query.h);
+
+    if !tile_in_bounds(state.canvas_size, region) {
+        return StatusCode::BAD_REQUEST
+            .with_body("tile region is out of range")
+            .into_response();
+    }
+
+    let generation = state.generation.load(Ordering::Acquire);
+    let key = CacheKey {
+        region: Some(region),
+        format,
+    };
+
+    let Ok((data, etag, last_modified)) = render_cached(state.0, generation, key, || {
+        let canvas = state.canvas.read().unwrap();
+        let tile = image::imageops::crop_imm(&*canvas, query.x, query.y, query.w, query.h).to_image();
+        encode_image(&tile, format)
+    }) else {
+        return StatusCode::INTERNAL_SERVER_ERROR
+            .with_body("failed to encode image")
+            .into_response();
+    };
+
+    conditional_image_response(req, data, etag, last_modified, content_type_for(format))
+}
+
+#[handler]
+#[expect(clippy::needless_pass_by_value)]
+fn set_pixel(state: Data<&ServerState>, req: &Request, Json(json): Json<Pixel>) -> Response {
     if json.x >= state.canvas_size.0 || json.y >= state.canvas_size.1 {
         return StatusCode::BAD_REQUEST
             .with_body("pixel outside of drawing area")
             .into_response();
     }
 
+    let ip = client_ip(state.trusted_proxy, req);
+
+    if let Err(retry_after) = state.rate_limiter.check(ip) {
+        return StatusCode::TOO_MANY_REQUESTS
+            .with_body("too many pixels, slow down")
+            .with_header("Retry-After", retry_after.as_secs_f64().ceil() as u64)
+            .into_response();
+    }
+
     state.queue.send(json).unwrap();
 
     StatusCode::NO_CONTENT.into()
 }
 
+/// Splits a binary batch body into fixed-width pixel records, or `None` if
+/// its length isn't a multiple of a record.
+fn decode_binary_batch(bytes: &[u8]) -> Option<Vec<Pixel>> {
+    if bytes.len() % PIXEL_RECORD_LEN != 0 {
+        return None;
+    }
+
+    Some(
+        bytes
+            .chunks_exact(PIXEL_RECORD_LEN)
+            .map(|record| decode_pixel(record.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+#[handler]
+#[expect(clippy::needless_pass_by_value)]
+async fn set_pixels(state: Data<&ServerState>, req: &Request, body: Body) -> Response {
+    let Ok(bytes) = body.into_bytes().await else {
+        return StatusCode::BAD_REQUEST
+            .with_body("failed to read request body")
+            .into_response();
+    };
+
+    // Compare only the media type, ignoring parameters like `; charset=utf-8`
+    // that a standards-compliant JSON client is free to send.
+    let is_json = req
+        .content_type()
+        .is_none_or(|content_type| {
+            content_type
+                .split(';')
+                .next()
+                .unwrap()
+                .trim()
+                .eq_ignore_ascii_case("application/json")
+        });
+
+    let pixels = if is_json {
+        serde_json::from_slice::<Vec<Pixel>>(&bytes).ok()
+    } else {
+        decode_binary_batch(&bytes)
+    };
+
+    let Some(pixels) = pixels else {
+        return StatusCode::BAD_REQUEST
+            .with_body("malformed pixel batch")
+            .into_response();
+    };
+
+    if pixels.len() > state.max_batch_size {
+        return StatusCode::BAD_REQUEST
+            .with_body(format!(
+                "batch of {} pixels exceeds the limit of {}",
+                pixels.len(),
+                state.max_batch_size
+            ))
+            .into_response();
+    }
+
+    if pixels
+        .iter()
+        .any(|pixel| pixel.x >= state.canvas_size.0 || pixel.y >= state.canvas_size.1)
+    {
+        return StatusCode::BAD_REQUEST
+            .with_body("pixel outside of drawing area")
+            .into_response();
+    }
+
+    if pixels.is_empty() {
+        return StatusCode::NO_CONTENT.into();
+    }
+
+    let ip = client_ip(state.trusted_proxy, req);
+
+    if let Err(retry_after) = state.rate_limiter.check_n(ip, pixels.len() as f64) {
+        return StatusCode::TOO_MANY_REQUESTS
+            .with_body("too many pixels, slow down")
+            .with_header("Retry-After", retry_after.as_secs_f64().ceil() as u64)
+            .into_response();
+    }
+
+    for pixel in pixels {
+        state.queue.send(pixel).unwrap();
+    }
+
+    StatusCode::NO_CONTENT.into()
+}
+
+#[handler]
+#[expect(clippy::needless_pass_by_value)]
+fn pixel_stream(state: Data<&ServerState>, ws: WebSocket) -> impl IntoResponse {
+    let canvas = state.canvas.clone();
+    let mut updates = state.pixel_updates.subscribe();
+
+    ws.on_upgrade(move |mut socket| async move {
+        let initial: Vec<u8> = {
+            let canvas = canvas.read().unwrap();
+            canvas
+                .enumerate_pixels()
+                .flat_map(|(x, y, pixel)| {
+                    encode_pixel(&Pixel {
+                        x,
+                        y,
+                        r: pixel.0[0],
+                        g: pixel.0[1],
+                        b: pixel.0[2],
+                    })
+                })
+                .collect()
+        };
+
+        if socket.send(Message::Binary(initial)).await.is_err() {
+            return;
+        }
+
+        while let Ok(batch) = updates.recv().await {
+            if socket.send(Message::Binary(batch.to_vec())).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct TimelapseQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    frames: Option<u32>,
+    format: Option<String>,
+}
+
+#[handler]
+#[expect(clippy::needless_pass_by_value)]
+fn get_timelapse(state: Data<&ServerState>, Query(query): Query<TimelapseQuery>) -> Response {
+    if query.format.is_some_and(|format| format != "gif") {
+        return StatusCode::BAD_REQUEST
+            .with_body("only the gif format is supported")
+            .into_response();
+    }
+
+    let frame_count = query.frames.unwrap_or(10);
+
+    if frame_count == 0 || frame_count > MAX_TIMELAPSE_FRAMES {
+        return StatusCode::BAD_REQUEST
+            .with_body(format!("frames must be between 1 and {MAX_TIMELAPSE_FRAMES}"))
+            .into_response();
+    }
+
+    let records = state.placement_log.read_records();
+
+    let from = query
+        .from
+        .unwrap_or_else(|| records.first().map_or(0, |record| record.timestamp_ms));
+    let to = query
+        .to
+        .unwrap_or_else(|| records.last().map_or(from, |record| record.timestamp_ms));
+
+    if to < from {
+        return StatusCode::BAD_REQUEST
+            .with_body("`to` must not precede `from`")
+            .into_response();
+    }
+
+    let mut frame = (*state.base_image).clone();
+    let mut records = records.iter().peekable();
+    let mut frames = Vec::with_capacity(frame_count as usize);
+
+    for step in 0..frame_count {
+        let target = if frame_count == 1 {
+            to
+        } else {
+            from + (to - from) * u64::from(step) / u64::from(frame_count - 1)
+        };
+
+        while records.peek().is_some_and(|record| record.timestamp_ms <= target) {
+            let record = records.next().unwrap();
+            frame.put_pixel(record.x, record.y, Rgb([record.r, record.g, record.b]));
+        }
+
+        frames.push(frame.clone());
+    }
+
+    let mut buffer = Cursor::new(Vec::new());
+    {
+        let mut encoder = GifEncoder::new(&mut buffer);
+
+        for frame in frames {
+            let rgba = image::DynamicImage::ImageRgb8(frame).to_rgba8();
+            encoder.encode_frame(Frame::new(rgba)).unwrap();
+        }
+    }
+
+    Response::from(buffer.into_inner())
+        .set_content_type("image/gif")
+        .into_response()
+}
+
+/// Redirects plain HTTP requests to the HTTPS listener, preserving the host
+/// and path.
+fn redirect_to_https(req: &Request) -> Response {
+    let host = req.header("Host").unwrap_or("localhost");
+    let target = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+
+    StatusCode::PERMANENT_REDIRECT
+        .with_header("Location", format!("https://{host}{target}"))
+        .into_response()
+}
+
+fn build_app(state: ServerState) -> impl Endpoint {
+    Route::new()
+        .at("/", StaticFileEndpoint::new("static/index.html"))
+        .at("/image", poem::get(get_image))
+        .at("/image/tile", poem::get(get_image_tile))
+        .at("/pixel", poem::post(set_pixel))
+        .at("/pixels", poem::post(set_pixels))
+        .at("/ws", poem::get(pixel_stream))
+        .at("/timelapse", poem::get(get_timelapse))
+        .with(Tracing)
+        .data(state)
+}
+
+async fn wait_for_shutdown_signal() {
+    let mut signals = Signals::new([Signal::Term, Signal::Int]).unwrap();
+    signals.next().await.unwrap().unwrap();
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt().init();
 
-    let canvas = {
+    let base_image = {
         let mut image_reader = ImageReader::open("data/image.png").unwrap();
         image_reader.set_format(ImageFormat::Png);
-        let image = image_reader.decode().unwrap().into_rgb8();
-        Arc::new(RwLock::new(image))
+        Arc::new(image_reader.decode().unwrap().into_rgb8())
     };
+    let canvas = Arc::new(RwLock::new((*base_image).clone()));
+
+    let placement_log = Arc::new(PlacementLog::open(PLACEMENT_LOG_PATH));
+
+    {
+        let placement_log = placement_log.clone();
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(LOG_FLUSH_INTERVAL);
+                placement_log.flush();
+            }
+        });
+    }
 
     let (tx, rx) = mpsc::channel::<Pixel>();
 
+    let rate_limiter = Arc::new(RateLimiter::from_env());
+
+    {
+        let rate_limiter = rate_limiter.clone();
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(RATE_LIMIT_SWEEP_INTERVAL);
+                rate_limiter.sweep();
+            }
+        });
+    }
+
+    let (pixel_updates, _) = broadcast::channel::<Arc<[u8]>>(PIXEL_BROADCAST_CAPACITY);
+    let generation = Arc::new(AtomicU64::new(0));
+
     {
         let canvas_clone = canvas.clone();
+        let pixel_updates = pixel_updates.clone();
+        let generation = generation.clone();
+        let placement_log = placement_log.clone();
 
         #[expect(clippy::significant_drop_tightening)]
         thread::spawn(move || {
             while let Ok(mut pixel) = rx.recv() {
                 let mut canvas = canvas_clone.write().unwrap();
+                let mut batch = Vec::new();
 
                 loop {
                     canvas.put_pixel(pixel.x, pixel.y, Rgb([pixel.r, pixel.g, pixel.b]));
+                    batch.extend_from_slice(&encode_pixel(&pixel));
+                    placement_log.append(&pixel);
 
                     let Ok(new_pixel) = rx.try_recv() else {
                         break;
@@ -130,40 +651,141 @@ async fn main() {
 
                     pixel = new_pixel;
                 }
+
+                drop(canvas);
+
+                generation.fetch_add(1, Ordering::Release);
+
+                // Ignore send errors: nobody is subscribed, which is fine.
+                let _ = pixel_updates.send(Arc::from(batch));
             }
         });
     }
 
-    let app = Route::new()
-        .at("/", StaticFileEndpoint::new("static/index.html"))
-        .at("/image", poem::get(get_image))
-        .at("/pixel", poem::post(set_pixel))
-        .with(Tracing)
-        .data(ServerState {
-            canvas: canvas.clone(),
-            canvas_size: {
-                let canvas = canvas.read().unwrap();
-                (canvas.width(), canvas.height())
-            },
-            canvas_cache: Arc::default(),
-            queue: Arc::new(tx),
-        });
+    let max_batch_size = std::env::var("MAX_BATCH_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BATCH_SIZE);
+
+    // A batch costs `max_batch_size` tokens in the worst case (see
+    // `set_pixels`), and `RateLimiter::check_n` never lets a charge be
+    // satisfied by more than a full bucket's worth of tokens. If the limit
+    // let batches outrun the bucket, the endpoint would reject every
+    // full-size batch forever instead of just rate-limiting it.
+    assert!(
+        (max_batch_size as f64) <= rate_limiter.capacity(),
+        "MAX_BATCH_SIZE ({max_batch_size}) must not exceed RATE_LIMIT_CAPACITY ({}), \
+         or a full batch could never be admitted",
+        rate_limiter.capacity(),
+    );
+
+    let state = ServerState {
+        canvas: canvas.clone(),
+        canvas_size: {
+            let canvas = canvas.read().unwrap();
+            (canvas.width(), canvas.height())
+        },
+        canvas_cache: Arc::default(),
+        queue: Arc::new(tx),
+        rate_limiter,
+        trusted_proxy: std::env::var("TRUSTED_PROXY").is_ok_and(|value| value == "1"),
+        pixel_updates,
+        generation,
+        placement_log: placement_log.clone(),
+        base_image: base_image.clone(),
+        max_batch_size,
+    };
+
+    let http_addr = std::env::var("HTTP_BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:80".to_owned());
+
+    if let Some(tls_paths) = TlsPaths::from_env() {
+        let https_addr =
+            std::env::var("HTTPS_BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:443".to_owned());
+        let https_redirect = std::env::var("HTTPS_REDIRECT").is_ok_and(|value| value == "1");
+
+        let (tls_tx, tls_rx) = watch::channel(tls::initial_config(&tls_paths));
+        tokio::spawn(tls::watch_and_reload(tls_tx, tls_paths));
+
+        let https_listener = TcpListener::bind(https_addr).rustls(WatchStream::new(tls_rx));
+        let https_server = Server::new(https_listener)
+            .run_with_graceful_shutdown(build_app(state.clone()), wait_for_shutdown_signal(), None);
 
-    Server::new(TcpListener::bind("0.0.0.0:80"))
-        .run_with_graceful_shutdown(
-            app,
-            async {
-                let mut signals = Signals::new([Signal::Term, Signal::Int]).unwrap();
-                signals.next().await.unwrap().unwrap();
-            },
+        let http_app = if https_redirect {
+            poem::endpoint::make_sync(redirect_to_https).boxed()
+        } else {
+            build_app(state.clone()).boxed()
+        };
+        let http_server = Server::new(TcpListener::bind(http_addr)).run_with_graceful_shutdown(
+            http_app,
+            wait_for_shutdown_signal(),
             None,
-        )
-        .await
-        .unwrap();
+        );
+
+        let (http_result, https_result) = tokio::join!(http_server, https_server);
+        http_result.unwrap();
+        https_result.unwrap();
+    } else {
+        Server::new(TcpListener::bind(http_addr))
+            .run_with_graceful_shutdown(build_app(state), wait_for_shutdown_signal(), None)
+            .await
+            .unwrap();
+    }
 
     canvas
         .read()
         .unwrap()
         .save_with_format("data/image.png", ImageFormat::Png)
         .unwrap();
+
+    placement_log.rotate();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_in_bounds_accepts_a_tile_that_fits() {
+        assert!(tile_in_bounds((100, 100), (10, 10, 20, 20)));
+        assert!(tile_in_bounds((100, 100), (0, 0, 100, 100)));
+    }
+
+    #[test]
+    fn tile_in_bounds_rejects_an_empty_tile() {
+        assert!(!tile_in_bounds((100, 100), (0, 0, 0, 10)));
+        assert!(!tile_in_bounds((100, 100), (0, 0, 10, 0)));
+    }
+
+    #[test]
+    fn tile_in_bounds_rejects_a_tile_overflowing_the_canvas() {
+        assert!(!tile_in_bounds((100, 100), (90, 0, 20, 10)));
+        assert!(!tile_in_bounds((100, 100), (0, 90, 10, 20)));
+    }
+
+    #[test]
+    fn tile_in_bounds_rejects_an_overflowing_offset_plus_size() {
+        assert!(!tile_in_bounds((100, 100), (u32::MAX, 0, 1, 1)));
+    }
+
+    #[test]
+    fn decode_binary_batch_round_trips_encode_pixel() {
+        let pixels = [
+            Pixel { x: 0, y: 0, r: 0, g: 0, b: 0 },
+            Pixel { x: 42, y: 7, r: 255, g: 128, b: 1 },
+        ];
+        let bytes: Vec<u8> = pixels.iter().flat_map(encode_pixel).collect();
+
+        assert_eq!(decode_binary_batch(&bytes).unwrap(), pixels);
+    }
+
+    #[test]
+    fn decode_binary_batch_accepts_an_empty_batch() {
+        assert_eq!(decode_binary_batch(&[]).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn decode_binary_batch_rejects_a_truncated_record() {
+        let bytes = vec![0u8; PIXEL_RECORD_LEN - 1];
+        assert!(decode_binary_batch(&bytes).is_none());
+    }
 }
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use poem::Request;
+
+/// How long a bucket may sit untouched before the sweep thread evicts it.
+const BUCKET_TTL: Duration = Duration::from_secs(300);
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Per-IP token bucket limiter guarding `POST /pixel`.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_rate: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn from_env() -> Self {
+        let capacity = std::env::var("RATE_LIMIT_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(10.0);
+
+        let refill_rate = std::env::var("RATE_LIMIT_REFILL_RATE")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1.0);
+
+        Self {
+            capacity,
+            refill_rate,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Attempts to spend one token for `ip`, refilling it first. Returns
+    /// `Ok(())` if the write is allowed, or `Err(retry_after)` with the
+    /// duration the caller should wait before trying again.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_n(ip, 1.0)
+    }
+
+    /// Like [`Self::check`], but spends `cost` tokens at once, so a batch of
+    /// `cost` pixels is charged fairly against the same bucket as `cost`
+    /// separate single-pixel writes would be — callers are expected to keep
+    /// `cost` at or below [`Self::capacity`] (see that method), since nothing
+    /// here will ever let a bucket hold more tokens than the capacity.
+    pub fn check_n(&self, ip: IpAddr, cost: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = buckets.entry(ip).or_insert_with(|| Bucket {
+            tokens: self.capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_rate).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            return Ok(());
+        }
+
+        let retry_after = (cost - bucket.tokens) / self.refill_rate;
+        Err(Duration::from_secs_f64(retry_after.max(0.0)))
+    }
+
+    /// The largest number of tokens a bucket can hold. Callers charging a
+    /// batched cost via [`Self::check_n`] must keep that cost at or below
+    /// this value, or the charge can never be satisfied no matter how long
+    /// the caller waits.
+    pub fn capacity(&self) -> f64 {
+        self.capacity
+    }
+
+    /// Drops buckets that haven't been touched in a while so idle clients
+    /// don't pin memory forever.
+    pub fn sweep(&self) {
+        let now = Instant::now();
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| now.duration_since(bucket.last_refill) < BUCKET_TTL);
+    }
+}
+
+/// Extracts the address to rate-limit on. In trusted-proxy mode the first
+/// hop of `X-Forwarded-For` is used instead of the TCP peer address, since
+/// the real client sits behind a reverse proxy.
+pub fn client_ip(trusted_proxy: bool, req: &Request) -> IpAddr {
+    if trusted_proxy {
+        let forwarded_ip = req
+            .header("X-Forwarded-For")
+            .and_then(|forwarded| forwarded.split(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+
+        if let Some(ip) = forwarded_ip {
+            return ip;
+        }
+    }
+
+    req.remote_addr()
+        .as_socket_addr()
+        .map(SocketAddr::ip)
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(capacity: f64, refill_rate: f64) -> RateLimiter {
+        RateLimiter {
+            capacity,
+            refill_rate,
+            buckets: Mutex::default(),
+        }
+    }
+
+    fn ip(last_octet: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, last_octet))
+    }
+
+    #[test]
+    fn check_n_allows_spending_up_to_the_full_capacity() {
+        let limiter = limiter(5.0, 1.0);
+        assert!(limiter.check_n(ip(1), 5.0).is_ok());
+    }
+
+    #[test]
+    fn check_n_rejects_spending_more_than_the_bucket_holds() {
+        let limiter = limiter(5.0, 1.0);
+        assert!(limiter.check_n(ip(1), 5.0).is_ok());
+        assert!(limiter.check_n(ip(1), 1.0).is_err());
+    }
+
+    #[test]
+    fn check_n_reports_a_retry_after_proportional_to_the_shortfall() {
+        let limiter = limiter(5.0, 2.0);
+        limiter.check_n(ip(1), 5.0).unwrap();
+
+        // One token short at a refill rate of 2/s should take ~0.5s.
+        let retry_after = limiter.check_n(ip(1), 1.0).unwrap_err();
+        assert!((retry_after.as_secs_f64() - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn check_n_tracks_each_ip_independently() {
+        let limiter = limiter(1.0, 1.0);
+        assert!(limiter.check_n(ip(1), 1.0).is_ok());
+        assert!(limiter.check_n(ip(2), 1.0).is_ok());
+        assert!(limiter.check_n(ip(1), 1.0).is_err());
+    }
+
+    #[test]
+    fn sweep_evicts_only_stale_buckets() {
+        let limiter = limiter(5.0, 1.0);
+        limiter.check_n(ip(1), 1.0).unwrap();
+        limiter.buckets.lock().unwrap().get_mut(&ip(1)).unwrap().last_refill =
+            Instant::now() - BUCKET_TTL - Duration::from_secs(1);
+
+        limiter.check_n(ip(2), 1.0).unwrap();
+        limiter.sweep();
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(!buckets.contains_key(&ip(1)));
+        assert!(buckets.contains_key(&ip(2)));
+    }
+}
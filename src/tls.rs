@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use async_signal::{Signal, Signals};
+use futures_util::StreamExt;
+use poem::listener::RustlsConfig;
+use tokio::sync::watch;
+
+/// How often the certificate files are checked for changes, on top of the
+/// immediate reload triggered by `SIGHUP`.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Paths to the certificate and private key used for the HTTPS listener,
+/// read once from the environment at startup.
+#[derive(Clone)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsPaths {
+    /// Reads `TLS_CERT_PATH` and `TLS_KEY_PATH` from the environment. Returns
+    /// `None` if either is unset, meaning HTTPS stays disabled.
+    pub fn from_env() -> Option<Self> {
+        let cert = std::env::var("TLS_CERT_PATH").ok()?.into();
+        let key = std::env::var("TLS_KEY_PATH").ok()?.into();
+        Some(Self { cert, key })
+    }
+
+    fn load(&self) -> RustlsConfig {
+        RustlsConfig::new()
+            .cert(std::fs::read(&self.cert).unwrap())
+            .key(std::fs::read(&self.key).unwrap())
+    }
+
+    fn last_modified(&self) -> Option<SystemTime> {
+        let cert = file_modified(&self.cert)?;
+        let key = file_modified(&self.key)?;
+        Some(cert.max(key))
+    }
+}
+
+fn file_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Watches the certificate files for changes and whenever one is found (or
+/// `SIGHUP` is received), loads the new certificate and publishes it over
+/// `tx` so every listener built from it picks up the renewed `CertifiedKey`
+/// without dropping existing connections.
+pub async fn watch_and_reload(tx: watch::Sender<RustlsConfig>, paths: TlsPaths) {
+    let mut signals = Signals::new([Signal::Hup]).unwrap();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let mut last_modified = paths.last_modified();
+
+    loop {
+        let sighup = tokio::select! {
+            _ = signals.next() => true,
+            _ = interval.tick() => false,
+        };
+
+        let modified = paths.last_modified();
+
+        if !sighup && modified == last_modified {
+            continue;
+        }
+
+        last_modified = modified;
+
+        if tx.send(paths.load()).is_err() {
+            return;
+        }
+    }
+}
+
+pub fn initial_config(paths: &TlsPaths) -> RustlsConfig {
+    paths.load()
+}
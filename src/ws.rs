@@ -0,0 +1,44 @@
+use crate::Pixel;
+
+/// Size in bytes of one encoded pixel delta: `x:u32, y:u32, r:u8, g:u8, b:u8`.
+pub const PIXEL_RECORD_LEN: usize = 11;
+
+/// Encodes a single pixel change into the compact binary format pushed over
+/// the `/ws` stream.
+pub fn encode_pixel(pixel: &Pixel) -> [u8; PIXEL_RECORD_LEN] {
+    let mut record = [0u8; PIXEL_RECORD_LEN];
+    record[0..4].copy_from_slice(&pixel.x.to_le_bytes());
+    record[4..8].copy_from_slice(&pixel.y.to_le_bytes());
+    record[8] = pixel.r;
+    record[9] = pixel.g;
+    record[10] = pixel.b;
+    record
+}
+
+/// Decodes a single record produced by [`encode_pixel`] back into a `Pixel`.
+pub fn decode_pixel(record: [u8; PIXEL_RECORD_LEN]) -> Pixel {
+    Pixel {
+        x: u32::from_le_bytes(record[0..4].try_into().unwrap()),
+        y: u32::from_le_bytes(record[4..8].try_into().unwrap()),
+        r: record[8],
+        g: record[9],
+        b: record[10],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_pixel_round_trips_encode_pixel() {
+        let pixel = Pixel { x: 123_456, y: 7, r: 10, g: 20, b: 30 };
+        assert_eq!(decode_pixel(encode_pixel(&pixel)), pixel);
+    }
+
+    #[test]
+    fn encode_pixel_uses_little_endian_coordinates() {
+        let pixel = Pixel { x: 1, y: 0, r: 0, g: 0, b: 0 };
+        assert_eq!(encode_pixel(&pixel)[0..4], [1, 0, 0, 0]);
+    }
+}